@@ -22,7 +22,10 @@ use tokio::process::Child;
 // ==============
 
 pub mod env;
+pub mod self_profile;
+pub mod size_profile;
 pub mod test;
+pub mod validate;
 
 
 pub const BINARYEN_VERSION_TO_INSTALL: u32 = 108;
@@ -38,6 +41,13 @@ pub const WASM_ARTIFACT_NAME: &str = "gui_wasm";
 
 pub const DEFAULT_TARGET_CRATE: &str = "app/gui";
 
+/// Number of largest sections/functions to include in a WASM size breakdown report.
+pub const SIZE_PROFILE_TOP_N: usize = 10;
+
+/// Where the lcov coverage report is written, relative to the repository root, when native tests
+/// are run with coverage instrumentation enabled.
+pub const COVERAGE_REPORT_PATH: &str = "target/coverage/lcov.info";
+
 #[derive(
 clap::ArgEnum,
 Clone,
@@ -85,7 +95,10 @@ pub enum Profile {
     Dev,
     Profile,
     Release,
-    // Production,
+    /// Like `Release`, but built with fat LTO and a single codegen unit for a maximally
+    /// optimized, CI-only artifact. Not meant for local development builds, as it is much
+    /// slower to compile.
+    Production,
 }
 
 impl From<Profile> for wasm_pack::Profile {
@@ -94,7 +107,7 @@ impl From<Profile> for wasm_pack::Profile {
             Profile::Dev => Self::Dev,
             Profile::Profile => Self::Profile,
             Profile::Release => Self::Release,
-            // Profile::Production => Self::Release,
+            Profile::Production => Self::Release,
         }
     }
 }
@@ -105,16 +118,16 @@ impl Profile {
             Profile::Dev => false,
             Profile::Profile => false,
             Profile::Release => true,
-            // Profile::Production => true,
+            Profile::Production => true,
         }
     }
 
     pub fn extra_rust_options(self) -> Vec<String> {
         match self {
-            // Profile::Production => ["-Clto=fat", "-Ccodegen-units=1", "-Cincremental=false"]
-            //     .into_iter()
-            //     .map(ToString::to_string)
-            //     .collect(),
+            Profile::Production => ["-Clto=fat", "-Ccodegen-units=1", "-Cincremental=false"]
+                .into_iter()
+                .map(ToString::to_string)
+                .collect(),
             Profile::Dev | Profile::Profile | Profile::Release => vec![],
         }
     }
@@ -124,6 +137,9 @@ impl Profile {
             Profile::Dev => wasm_opt::OptimizationLevel::O0,
             Profile::Profile => wasm_opt::OptimizationLevel::O,
             Profile::Release => wasm_opt::OptimizationLevel::O3,
+            // Optimize for size by default; pass an explicit `-O4` through `wasm_opt_options` to
+            // prioritize speed instead.
+            Profile::Production => wasm_opt::OptimizationLevel::Oz,
         }
     }
 }
@@ -142,12 +158,46 @@ pub struct BuildInput {
     pub uncollapsed_log_level: LogLevel,
     pub wasm_size_limit: Option<byte_unit::Byte>,
     pub system_shader_tools: bool,
+    /// Always log a per-section and per-function size breakdown of the built WASM, rather than
+    /// only when `wasm_size_limit` is exceeded.
+    pub wasm_size_profile: bool,
+    /// Time the compile step and [`Wasm::finalize_wasm`] (`wasm-opt` or a plain copy) as build
+    /// phases and write a `chrome://tracing`-compatible trace next to the built artifact. Copying
+    /// [`Artifact::files_to_ship`] is not timed.
+    pub self_profile: bool,
+    /// Instrument native `cargo test` runs with LLVM source-based coverage
+    /// (`-Cinstrument-coverage`) and render an lcov + per-crate summary report after the run.
+    pub coverage: bool,
+    /// Skip the structural validation of the finalized WASM binary, symmetric to
+    /// [`Self::skip_wasm_opt`].
+    pub skip_wasm_validation: bool,
 }
 
 impl BuildInput {
+    /// Set up a [`self_profile::SelfProfiler`] if [`Self::self_profile`] is enabled.
+    pub fn self_profiler(&self) -> Option<self_profile::SelfProfiler> {
+        self.self_profile.then(self_profile::SelfProfiler::new)
+    }
+
+    /// If self-profiling is enabled, write the collected phase timings as a Chrome trace next to
+    /// `wasm_path`.
+    pub fn perhaps_write_self_profile(
+        &self,
+        profiler: &self_profile::SelfProfiler,
+        wasm_path: impl AsRef<Path>,
+    ) -> Result {
+        if self.self_profile {
+            let trace_path = wasm_path.as_ref().with_extension("trace.json");
+            profiler.write_chrome_trace(&trace_path)?;
+            info!("Wrote build self-profile trace to {}.", trace_path.display());
+        }
+        Ok(())
+    }
+
     pub async fn perhaps_check_size(&self, wasm_path: impl AsRef<Path>) -> Result {
         let compressed_size = compressed_size(&wasm_path).await?.get_appropriate_unit(true);
         info!("Compressed size of {} is {}.", wasm_path.as_ref().display(), compressed_size);
+        let mut limit_exceeded = false;
         if let Some(wasm_size_limit) = self.wasm_size_limit {
             let wasm_size_limit = wasm_size_limit.get_appropriate_unit(true);
             if !self.profile.should_check_size() {
@@ -160,18 +210,55 @@ impl BuildInput {
                     ProfilingLevel::Objective
                 );
             } else {
-                ensure!(
-                    compressed_size < wasm_size_limit,
-                    "Compressed WASM size ~{} ({} bytes) exceeds the limit of {} ({} bytes).",
-                    compressed_size,
-                    compressed_size.get_byte(),
-                    wasm_size_limit,
-                    wasm_size_limit.get_byte(),
-                )
+                limit_exceeded = compressed_size >= wasm_size_limit;
             }
         }
+
+        if limit_exceeded || self.wasm_size_profile {
+            self.report_size_profile(&wasm_path).await;
+        }
+
+        if let Some(wasm_size_limit) = self.wasm_size_limit {
+            let wasm_size_limit = wasm_size_limit.get_appropriate_unit(true);
+            ensure!(
+                !limit_exceeded,
+                "Compressed WASM size ~{} ({} bytes) exceeds the limit of {} ({} bytes).",
+                compressed_size,
+                compressed_size.get_byte(),
+                wasm_size_limit,
+                wasm_size_limit.get_byte(),
+            )
+        }
         Ok(())
     }
+
+    /// Parse the WASM binary and log its largest sections and functions, to help explain what
+    /// grew the artifact. Parsing failures are logged but not fatal, as this is a diagnostic aid.
+    async fn report_size_profile(&self, wasm_path: impl AsRef<Path>) {
+        match size_profile::profile(&wasm_path).await {
+            Ok(report) => {
+                let report = report.top(SIZE_PROFILE_TOP_N);
+                info!(
+                    "WASM size breakdown for {}:\n{}",
+                    wasm_path.as_ref().display(),
+                    report.to_table()
+                );
+            }
+            Err(e) => warn!("Failed to compute WASM size breakdown: {e}"),
+        }
+    }
+
+    /// Structurally validate the finalized WASM binary before it is shipped, unless
+    /// [`Self::skip_wasm_validation`] is set.
+    pub async fn perhaps_validate_wasm(&self, wasm_path: impl AsRef<Path>) -> Result {
+        if self.skip_wasm_validation {
+            debug!("Skipping WASM validation, as it was explicitly requested.");
+            return Ok(());
+        }
+        validate::validate(&wasm_path)
+            .await
+            .with_context(|| format!("Validating {}.", wasm_path.as_ref().display()))
+    }
 }
 
 #[derive(Clone, Copy, Debug, PartialEq, Eq)]
@@ -181,6 +268,8 @@ pub struct Wasm;
 #[derivative(Debug)]
 pub struct WatchInput {
     pub cargo_watch_options: Vec<String>,
+    /// See [`BuildInput::coverage`].
+    pub coverage: bool,
 }
 
 #[derive(Clone, Debug, Display, PartialEq, Eq)]
@@ -232,7 +321,17 @@ impl Wasm {
             .await
     }
 
-    pub async fn test(&self, repo_root: PathBuf, wasm: &[test::Browser], native: bool) -> Result {
+    /// Run the native and/or `wasm-pack`-driven test suites.
+    ///
+    /// `coverage` only affects the native run; see [`Self::integration_test`] for why it can't
+    /// apply to the WASM-hosted one. Any existing caller must be updated to pass it explicitly.
+    pub async fn test(
+        &self,
+        repo_root: PathBuf,
+        wasm: &[test::Browser],
+        native: bool,
+        coverage: bool,
+    ) -> Result {
         async fn maybe_run<Fut: Future<Output=Result>>(
             name: &str,
             enabled: bool,
@@ -248,11 +347,22 @@ impl Wasm {
         }
 
         maybe_run("native", native, async || {
-            Cargo
-                .cmd()?
-                .current_dir(repo_root.clone())
-                .apply(&cargo::Command::Test)
-                .apply(&cargo::Options::Workspace)
+            let mut cargo_cmd = Cargo.cmd()?;
+            cargo_cmd.current_dir(repo_root.clone());
+            if coverage {
+                // `cargo llvm-cov` builds with `-Cinstrument-coverage`, points `LLVM_PROFILE_FILE`
+                // at a per-test `.profraw` directory, and merges/renders the result for us, so we
+                // don't have to orchestrate the `.profraw` files ourselves.
+                cargo_cmd
+                    .arg("llvm-cov")
+                    .apply(&cargo::Options::Workspace)
+                    .arg("--lcov")
+                    .arg("--output-path")
+                    .arg(repo_root.join(COVERAGE_REPORT_PATH));
+            } else {
+                cargo_cmd.apply(&cargo::Command::Test).apply(&cargo::Options::Workspace);
+            }
+            cargo_cmd
                 // Color needs to be passed to tests themselves separately.
                 // See: https://github.com/rust-lang/cargo/issues/1983
                 .arg("--")
@@ -262,10 +372,33 @@ impl Wasm {
         })
             .await?;
 
+        if native && coverage {
+            // Re-render the profiling data `cargo llvm-cov` just collected as a short per-crate
+            // percentage table, without re-running the tests.
+            Cargo
+                .cmd()?
+                .current_dir(repo_root.clone())
+                .arg("llvm-cov")
+                .arg("report")
+                .apply(&cargo::Options::Workspace)
+                .run_ok()
+                .await
+                .context("Rendering coverage summary report.")?;
+            info!(
+                "Coverage report written to {}.",
+                repo_root.join(COVERAGE_REPORT_PATH).display()
+            );
+        }
+
         maybe_run("wasm", !wasm.is_empty(), || test::test_all(repo_root.clone(), wasm)).await?;
         Ok(())
     }
 
+    /// Run the browser-hosted WASM integration test suite.
+    ///
+    /// `coverage` exists to keep this signature symmetric with [`Self::test`]; it is always
+    /// ignored here (with a warning) since there is no coverage runtime available in that
+    /// environment. Any existing caller must be updated to pass it explicitly.
     pub async fn integration_test(
         &self,
         source_root: PathBuf,
@@ -273,7 +406,14 @@ impl Wasm {
         headless: bool,
         additional_options: Vec<String>,
         wasm_timeout: Option<Duration>,
+        coverage: bool,
     ) -> Result {
+        if coverage {
+            // Browser-run WASM tests have no LLVM profiling runtime to flush `.profraw` files to,
+            // so source-based coverage instrumentation is not available here, unlike for the
+            // native tests in `Wasm::test`.
+            warn!("Coverage collection is not supported for WASM integration tests, ignoring.");
+        }
         info!("Running Rust WASM test suite.");
         use wasm_pack::TestFlags::*;
         WasmPack
@@ -294,12 +434,74 @@ impl Wasm {
         // PM will be automatically killed by dropping the handle.
     }
 
+    /// Compile the crate to WASM with `wasm-pack`, then finalize the resulting artifact
+    /// (wasm-opt or copy).
+    ///
+    /// `input.profile`'s [`Profile::extra_rust_options`] are passed as `RUSTFLAGS` to the
+    /// `wasm-pack` invocation, which is the step that actually runs the `wasm32` compile.
+    ///
+    /// If [`BuildInput::self_profile`] is enabled, the compile step and [`Self::finalize_wasm`]
+    /// are each timed as their own phase, and the collected trace is written next to the
+    /// finalized artifact.
+    pub async fn build(
+        &self,
+        repo_root: PathBuf,
+        input: &BuildInput,
+        temp_dist: &RepoRootDistWasm,
+    ) -> Result {
+        let self_profiler = input.self_profiler();
+        let rust_options = input.profile.extra_rust_options();
+
+        let compile = async {
+            let mut wasm_pack_cmd = WasmPack.cmd()?;
+            wasm_pack_cmd.current_dir(&repo_root);
+            if !rust_options.is_empty() {
+                wasm_pack_cmd.env("RUSTFLAGS", rust_options.join(" "));
+            }
+            wasm_pack_cmd
+                .build()
+                .apply(&wasm_pack::Profile::from(input.profile))
+                .arg("--target")
+                .arg("web")
+                .arg("--out-dir")
+                .arg(&temp_dist.path)
+                .arg("--out-name")
+                .arg(OUTPUT_NAME)
+                .arg(&input.crate_path)
+                .arg("--")
+                .args(&input.extra_cargo_options)
+                .run_ok()
+                .await
+        };
+        // Now that `compile` is the real wasm-pack invocation, this phase actually measures the
+        // WASM compile step, not a parallel `cargo build` that never produced the shipped artifact.
+        match &self_profiler {
+            Some(profiler) => profiler.phase("compile", || compile).await,
+            None => compile.await,
+        }?;
+
+        Self::finalize_wasm(
+            &input.wasm_opt_options,
+            input.skip_wasm_opt,
+            input.profile,
+            temp_dist,
+            self_profiler.as_ref(),
+        )
+        .await?;
+
+        if let Some(profiler) = &self_profiler {
+            input.perhaps_write_self_profile(profiler, &temp_dist.pkg_opt_wasm)?;
+        }
+        Ok(())
+    }
+
     /// Process "raw" WASM (as compiled) by optionally invoking wasm-opt.
     pub async fn finalize_wasm(
         wasm_opt_options: &[String],
         skip_wasm_opt: bool,
         profile: Profile,
         temp_dist: &RepoRootDistWasm,
+        self_profiler: Option<&self_profile::SelfProfiler>,
     ) -> Result {
         let should_call_wasm_opt = {
             if profile == Profile::Dev {
@@ -313,23 +515,30 @@ impl Wasm {
             }
         };
 
-        if should_call_wasm_opt {
-            let mut wasm_opt_command = WasmOpt.cmd()?;
-            let has_custom_opt_level = wasm_opt_options.iter().any(|opt| {
-                wasm_opt::OptimizationLevel::from_str(opt.trim_start_matches('-')).is_ok()
-            });
-            if !has_custom_opt_level {
-                wasm_opt_command.apply(&profile.optimization_level());
+        let finalize = async {
+            if should_call_wasm_opt {
+                let mut wasm_opt_command = WasmOpt.cmd()?;
+                let has_custom_opt_level = wasm_opt_options.iter().any(|opt| {
+                    wasm_opt::OptimizationLevel::from_str(opt.trim_start_matches('-')).is_ok()
+                });
+                if !has_custom_opt_level {
+                    wasm_opt_command.apply(&profile.optimization_level());
+                }
+                wasm_opt_command
+                    .args(wasm_opt_options)
+                    .arg(&temp_dist.pkg_wasm)
+                    .apply(&wasm_opt::Output(&temp_dist.pkg_opt_wasm))
+                    .run_ok()
+                    .await?;
+            } else {
+                copy_file_if_different(&temp_dist.pkg_wasm, &temp_dist.pkg_opt_wasm)?;
             }
-            wasm_opt_command
-                .args(wasm_opt_options)
-                .arg(&temp_dist.pkg_wasm)
-                .apply(&wasm_opt::Output(&temp_dist.pkg_opt_wasm))
-                .run_ok()
-                .await?;
-        } else {
-            copy_file_if_different(&temp_dist.pkg_wasm, &temp_dist.pkg_opt_wasm)?;
+            Ok(())
+        };
+
+        match self_profiler {
+            Some(self_profiler) => self_profiler.phase("finalize_wasm", || finalize).await,
+            None => finalize.await,
         }
-        Ok(())
     }
 }
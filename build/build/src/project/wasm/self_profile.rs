@@ -0,0 +1,94 @@
+//! A minimal self-profiler for the WASM build pipeline.
+//!
+//! This lets us measure which phase of a multi-minute build (the `cargo`/`wasm-pack` compile,
+//! `wasm-opt`, copying files to ship, ...) is actually slow, rather than guessing from `info!`
+//! log timestamps. It is modeled on rustc's `SelfProfiler`/`SelfProfilerRef`: phases are timed
+//! as they run and the collected timings are dumped as a `chrome://tracing`-compatible trace
+//! that can be loaded in a standard viewer.
+
+use crate::prelude::*;
+
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::time::Duration;
+use std::time::Instant;
+
+
+// ===================
+// === PhaseTiming ===
+// ===================
+
+/// A single recorded build phase: when it started (relative to profiler creation) and how long
+/// it took.
+#[derive(Clone, Debug)]
+struct PhaseTiming {
+    name:     String,
+    start:    Duration,
+    duration: Duration,
+}
+
+/// Format a duration the way a human would want to read it in a log line, e.g. `12.345s`.
+/// Mirrors rustc's `duration_to_secs_str`.
+pub fn duration_to_secs_str(duration: Duration) -> String {
+    format!("{:.3}s", duration.as_secs_f64())
+}
+
+
+
+// ===================
+// === SelfProfiler ===
+// ===================
+
+/// Collects timings for named build phases and can dump them as a Chrome trace.
+///
+/// Cheaply cloneable; all clones share the same underlying event log.
+#[derive(Clone, Debug)]
+pub struct SelfProfiler {
+    epoch:  Instant,
+    events: Arc<Mutex<Vec<PhaseTiming>>>,
+}
+
+impl Default for SelfProfiler {
+    fn default() -> Self {
+        Self { epoch: Instant::now(), events: Arc::new(Mutex::new(Vec::new())) }
+    }
+}
+
+impl SelfProfiler {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Run `f`, recording its wall-clock duration under `name`.
+    pub async fn phase<Fut, T>(&self, name: impl Into<String>, f: impl FnOnce() -> Fut) -> T
+    where Fut: Future<Output = T> {
+        let name = name.into();
+        let start = self.epoch.elapsed();
+        let phase_begin = Instant::now();
+        let result = f().await;
+        let duration = phase_begin.elapsed();
+        debug!("Phase '{name}' took {}.", duration_to_secs_str(duration));
+        self.events.lock().unwrap().push(PhaseTiming { name, start, duration });
+        result
+    }
+
+    /// Serialize the collected phase timings as a `chrome://tracing`-compatible JSON array of
+    /// complete ("X") events, and write it to `path`.
+    pub fn write_chrome_trace(&self, path: impl AsRef<Path>) -> Result {
+        let events = self.events.lock().unwrap();
+        let trace_events: Vec<_> = events
+            .iter()
+            .map(|event| {
+                serde_json::json!({
+                    "name": event.name,
+                    "ph": "X",
+                    "ts": event.start.as_micros() as u64,
+                    "dur": event.duration.as_micros() as u64,
+                    "pid": 0,
+                    "tid": 0,
+                })
+            })
+            .collect();
+        ide_ci::fs::write_json(path, &serde_json::Value::Array(trace_events))
+    }
+}
@@ -0,0 +1,444 @@
+//! A minimal WASM module parser used to break down the binary's size by section and,
+//! within the code section, by function.
+//!
+//! This is deliberately not a general-purpose WASM parser: it only decodes as much of the
+//! module structure (section headers, LEB128 length prefixes, the Import section's function
+//! imports and the `name` custom section) as is needed to attribute bytes to sections and functions. It is modeled on how `rustc`
+//! reports code size breakdowns (see `rustc_middle::ty::print::PrettyPrinter` and
+//! `rustc_session::code_stats::CodeStats`): accumulate sizes into named buckets, then print
+//! the largest contributors first.
+
+use crate::prelude::*;
+
+use std::collections::BTreeMap;
+
+
+// =================
+// === SizeEntry ===
+// =================
+
+/// A single named contributor to the binary's size, e.g. a section or a function.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SizeEntry {
+    pub name: String,
+    pub size: u64,
+}
+
+impl SizeEntry {
+    fn new(name: impl Into<String>, size: u64) -> Self {
+        Self { name: name.into(), size }
+    }
+}
+
+/// Sort `entries` by descending size and keep only the `limit` largest ones.
+fn top_n(mut entries: Vec<SizeEntry>, limit: usize) -> Vec<SizeEntry> {
+    entries.sort_by(|a, b| b.size.cmp(&a.size));
+    entries.truncate(limit);
+    entries
+}
+
+
+
+// ==================
+// === SizeReport ===
+// ==================
+
+/// Breakdown of a WASM module's size by section and, where available, by function.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct SizeReport {
+    /// Sections, in section id order until [`Self::top`] sorts them largest first.
+    pub sections: Vec<SizeEntry>,
+    /// Functions, in the order the `name` section lists them until [`Self::top`] sorts them
+    /// largest first. Empty if the module carries no `name` custom section.
+    pub functions: Vec<SizeEntry>,
+}
+
+impl SizeReport {
+    /// Keep only the `limit` largest sections and functions.
+    pub fn top(self, limit: usize) -> Self {
+        Self { sections: top_n(self.sections, limit), functions: top_n(self.functions, limit) }
+    }
+
+    /// Render the report as a human-readable table, e.g. for logging.
+    pub fn to_table(&self) -> String {
+        let mut out = String::new();
+        out.push_str("Sections by size:\n");
+        for entry in &self.sections {
+            out.push_str(&format!("  {:>10} bytes  {}\n", entry.size, entry.name));
+        }
+        if !self.functions.is_empty() {
+            out.push_str("Functions by size:\n");
+            for entry in &self.functions {
+                out.push_str(&format!("  {:>10} bytes  {}\n", entry.size, entry.name));
+            }
+        }
+        out
+    }
+}
+
+
+
+// ===================
+// === WASM parser ===
+// ===================
+
+pub(super) const WASM_MAGIC: [u8; 4] = *b"\0asm";
+pub(super) const WASM_VERSION: [u8; 4] = [1, 0, 0, 0];
+
+/// Names of the section ids defined by the WASM binary format (core spec + the "custom"
+/// section id 0). Sections not listed here (e.g. future additions) are reported by their id.
+fn section_name(id: u8) -> String {
+    let name = match id {
+        0 => "Custom",
+        1 => "Type",
+        2 => "Import",
+        3 => "Function",
+        4 => "Table",
+        5 => "Memory",
+        6 => "Global",
+        7 => "Export",
+        8 => "Start",
+        9 => "Element",
+        10 => "Code",
+        11 => "Data",
+        12 => "DataCount",
+        _ => return format!("Unknown section {id}"),
+    };
+    name.to_string()
+}
+
+/// Read an unsigned LEB128 integer starting at `*pos`, advancing `*pos` past it.
+pub(super) fn read_uleb128(bytes: &[u8], pos: &mut usize) -> Result<u64> {
+    let mut result = 0u64;
+    let mut shift = 0u32;
+    loop {
+        let byte = *bytes.get(*pos).ok_or_else(|| anyhow!("Truncated LEB128 at offset {pos}."))?;
+        *pos += 1;
+        result |= u64::from(byte & 0x7f) << shift;
+        if byte & 0x80 == 0 {
+            return Ok(result);
+        }
+        shift += 7;
+        ensure!(shift < 64, "LEB128 integer at offset {pos} is too large.");
+    }
+}
+
+/// Read `len` bytes starting at `*pos`, advancing `*pos` past them.
+pub(super) fn read_bytes<'a>(bytes: &'a [u8], pos: &mut usize, len: usize) -> Result<&'a [u8]> {
+    let end = pos.checked_add(len).ok_or_else(|| anyhow!("Section length overflow."))?;
+    let slice = bytes
+        .get(*pos..end)
+        .ok_or_else(|| anyhow!("Section of {len} bytes at offset {pos} runs past end of file."))?;
+    *pos = end;
+    Ok(slice)
+}
+
+/// Per-function body sizes, in function index order, as they appear in the code section.
+pub(super) fn code_section_function_sizes(payload: &[u8]) -> Result<Vec<u64>> {
+    let mut pos = 0;
+    let count = read_uleb128(payload, &mut pos)?;
+    let mut sizes = Vec::with_capacity(count as usize);
+    for _ in 0..count {
+        let body_len = read_uleb128(payload, &mut pos)?;
+        read_bytes(payload, &mut pos, body_len as usize)?;
+        sizes.push(body_len);
+    }
+    Ok(sizes)
+}
+
+/// Number of function imports declared by the Import section (kind byte `0x00`). The global
+/// function index space used by the "name" section's function-name subsection numbers imported
+/// functions first, followed by the locally-defined functions that actually live in the Code
+/// section — so this count is needed to translate a global function index into a Code-section-
+/// relative (local) index.
+fn import_section_function_count(payload: &[u8]) -> Result<u32> {
+    let mut pos = 0;
+    let count = read_uleb128(payload, &mut pos)?;
+    let mut function_count = 0;
+    for _ in 0..count {
+        skip_name(payload, &mut pos)?; // module name
+        skip_name(payload, &mut pos)?; // field name
+        let kind = *payload
+            .get(pos)
+            .ok_or_else(|| anyhow!("Truncated import descriptor at offset {pos}."))?;
+        pos += 1;
+        match kind {
+            0 => {
+                read_uleb128(payload, &mut pos)?; // type index
+                function_count += 1;
+            }
+            1 => {
+                pos += 1; // reftype
+                skip_limits(payload, &mut pos)?;
+            }
+            2 => skip_limits(payload, &mut pos)?,
+            3 => pos += 2, // valtype + mutability
+            other => bail!("Unknown import kind {other} at offset {pos}."),
+        }
+    }
+    Ok(function_count)
+}
+
+/// Skip a length-prefixed UTF-8 string (module/field name).
+fn skip_name(bytes: &[u8], pos: &mut usize) -> Result<()> {
+    let len = read_uleb128(bytes, pos)?;
+    read_bytes(bytes, pos, len as usize)?;
+    Ok(())
+}
+
+/// Skip a `limits` value: a flag byte followed by one or two LEB128 integers.
+fn skip_limits(bytes: &[u8], pos: &mut usize) -> Result<()> {
+    let has_max = *bytes.get(*pos).ok_or_else(|| anyhow!("Truncated limits at offset {pos}."))?;
+    *pos += 1;
+    read_uleb128(bytes, pos)?; // min
+    if has_max == 1 {
+        read_uleb128(bytes, pos)?; // max
+    }
+    Ok(())
+}
+
+/// Function names declared by the "name" custom section's function-name subsection (id 1),
+/// keyed by function index. Other subsections (module names, local names, ...) are ignored.
+fn name_section_function_names(payload: &[u8]) -> Result<Vec<(u32, String)>> {
+    let mut pos = 0;
+    let mut names = Vec::new();
+    while pos < payload.len() {
+        let subsection_id = *payload
+            .get(pos)
+            .ok_or_else(|| anyhow!("Truncated name subsection header at offset {pos}."))?;
+        pos += 1;
+        let subsection_len = read_uleb128(payload, &mut pos)?;
+        let subsection = read_bytes(payload, &mut pos, subsection_len as usize)?;
+        if subsection_id == 1 {
+            let mut sub_pos = 0;
+            let count = read_uleb128(subsection, &mut sub_pos)?;
+            for _ in 0..count {
+                let func_index = read_uleb128(subsection, &mut sub_pos)? as u32;
+                let name_len = read_uleb128(subsection, &mut sub_pos)?;
+                let name_bytes = read_bytes(subsection, &mut sub_pos, name_len as usize)?;
+                let name = String::from_utf8_lossy(name_bytes).into_owned();
+                names.push((func_index, name));
+            }
+        }
+    }
+    Ok(names)
+}
+
+/// Parse a WASM module and break its size down by section and, if a `name` custom section is
+/// present, by function. Used to diagnose what inflated the binary when it exceeds
+/// [`super::BuildInput::wasm_size_limit`].
+pub async fn profile(wasm_path: impl AsRef<Path>) -> Result<SizeReport> {
+    let bytes = tokio::fs::read(wasm_path.as_ref()).await?;
+    parse_bytes(&bytes)
+        .with_context(|| format!("Parsing WASM module at {}.", wasm_path.as_ref().display()))
+}
+
+/// The synchronous, path-free core of [`profile`], split out so it can be unit tested without
+/// touching the filesystem.
+fn parse_bytes(bytes: &[u8]) -> Result<SizeReport> {
+    ensure!(bytes.len() >= 8, "File is too small to be a WASM module.");
+    ensure!(bytes[0..4] == WASM_MAGIC, "File does not start with the WASM magic number.");
+    ensure!(bytes[4..8] == WASM_VERSION, "File has an unsupported WASM version.");
+
+    let mut pos = 8;
+    let mut section_sizes = BTreeMap::<u8, u64>::new();
+    let mut code_sizes = Vec::new();
+    let mut imported_function_count = 0u32;
+    let mut name_section = None;
+    while pos < bytes.len() {
+        let id = bytes[pos];
+        pos += 1;
+        let payload_len = read_uleb128(bytes, &mut pos)?;
+        let payload = read_bytes(bytes, &mut pos, payload_len as usize)?;
+
+        if id == 10 {
+            code_sizes = code_section_function_sizes(payload)?;
+        } else if id == 2 {
+            imported_function_count = import_section_function_count(payload)?;
+        } else if id == 0 {
+            if let Some(name) = custom_section_name(payload) {
+                if name == "name" {
+                    name_section = Some(&payload[name_section_header_len(payload)..]);
+                }
+            }
+        }
+
+        *section_sizes.entry(id).or_default() += payload_len;
+    }
+
+    let sections = section_sizes
+        .into_iter()
+        .map(|(id, size)| SizeEntry::new(section_name(id), size))
+        .collect();
+
+    let functions = if let Some(name_section) = name_section {
+        match name_section_function_names(name_section) {
+            Ok(names) => names
+                .into_iter()
+                // The name section indexes functions in the *global* function index space
+                // (imports first, then locally-defined functions), while `code_sizes` only
+                // covers locally-defined functions. Imported functions have no entry in the
+                // Code section at all, so translate to a local index and drop the imports.
+                .filter_map(|(index, name)| {
+                    let local_index = index.checked_sub(imported_function_count)?;
+                    code_sizes.get(local_index as usize).map(|&size| SizeEntry::new(name, size))
+                })
+                .collect(),
+            Err(e) => {
+                warn!("Failed to parse WASM name section, function-level sizes unavailable: {e}");
+                Vec::new()
+            }
+        }
+    } else {
+        Vec::new()
+    };
+
+    Ok(SizeReport { sections, functions })
+}
+
+/// Read the name of a custom section (id 0): a length-prefixed UTF-8 string at its start.
+fn custom_section_name(payload: &[u8]) -> Option<String> {
+    let mut pos = 0;
+    let len = read_uleb128(payload, &mut pos).ok()?;
+    let name_bytes = read_bytes(payload, &mut pos, len as usize).ok()?;
+    Some(String::from_utf8_lossy(name_bytes).into_owned())
+}
+
+/// Number of bytes occupied by the custom section's name field, to be skipped before parsing
+/// its subsections.
+fn name_section_header_len(payload: &[u8]) -> usize {
+    let mut pos = 0;
+    if let Ok(len) = read_uleb128(payload, &mut pos) {
+        pos += len as usize;
+    }
+    pos
+}
+
+
+// ====================
+// === test_support ===
+// ====================
+
+/// Small builders for hand-rolled WASM binaries, shared with [`super::validate`]'s tests.
+#[cfg(test)]
+pub(crate) mod test_support {
+    use super::*;
+
+    /// Encode `value` as an unsigned LEB128 integer.
+    pub fn uleb(mut value: u64) -> Vec<u8> {
+        let mut out = Vec::new();
+        loop {
+            let byte = (value & 0x7f) as u8;
+            value >>= 7;
+            if value == 0 {
+                out.push(byte);
+                break;
+            }
+            out.push(byte | 0x80);
+        }
+        out
+    }
+
+    /// A length-prefixed UTF-8 string, as used for module/field/function names.
+    pub fn name(s: &str) -> Vec<u8> {
+        let mut out = uleb(s.len() as u64);
+        out.extend_from_slice(s.as_bytes());
+        out
+    }
+
+    /// Wrap `payload` in a section header with the given id.
+    pub fn section(id: u8, payload: Vec<u8>) -> Vec<u8> {
+        let mut out = vec![id];
+        out.extend(uleb(payload.len() as u64));
+        out.extend(payload);
+        out
+    }
+
+    /// The magic number and version fields every module starts with.
+    pub fn header() -> Vec<u8> {
+        let mut out = Vec::new();
+        out.extend_from_slice(&WASM_MAGIC);
+        out.extend_from_slice(&WASM_VERSION);
+        out
+    }
+}
+
+
+// =============
+// === Tests ===
+// =============
+
+#[cfg(test)]
+mod tests {
+    use super::test_support::*;
+    use super::*;
+
+    #[test]
+    fn empty_module_has_no_sections() {
+        let report = parse_bytes(&header()).unwrap();
+        assert!(report.sections.is_empty());
+        assert!(report.functions.is_empty());
+    }
+
+    #[test]
+    fn truncated_leb128_is_invalid() {
+        let mut module = header();
+        module.push(1); // section id
+        module.push(0x80); // continuation byte with nothing following
+        assert!(parse_bytes(&module).is_err());
+    }
+
+    #[test]
+    fn function_sizes_are_translated_from_global_to_local_indices() {
+        let import_payload = {
+            let mut out = uleb(1); // one import
+            out.extend(name("env"));
+            out.extend(name("f"));
+            out.push(0); // kind: func
+            out.extend(uleb(0)); // type index
+            out
+        };
+
+        let code_payload = {
+            let mut out = uleb(2); // two locally-defined functions
+            out.extend(uleb(3));
+            out.extend([0u8; 3]);
+            out.extend(uleb(5));
+            out.extend([0u8; 5]);
+            out
+        };
+
+        let function_names_payload = {
+            let mut out = uleb(3);
+            out.extend(uleb(0)); // global index 0: the imported function
+            out.extend(name("imported_fn"));
+            out.extend(uleb(1)); // global index 1: first local function
+            out.extend(name("local_a"));
+            out.extend(uleb(2)); // global index 2: second local function
+            out.extend(name("local_b"));
+            out
+        };
+        let name_subsection = {
+            let mut out = vec![1u8]; // function-names subsection
+            out.extend(uleb(function_names_payload.len() as u64));
+            out.extend(function_names_payload);
+            out
+        };
+        let name_custom_payload = {
+            let mut out = name("name");
+            out.extend(name_subsection);
+            out
+        };
+
+        let mut module = header();
+        module.extend(section(2, import_payload));
+        module.extend(section(10, code_payload));
+        module.extend(section(0, name_custom_payload));
+
+        let report = parse_bytes(&module).unwrap();
+        assert_eq!(report.functions, vec![
+            SizeEntry::new("local_a", 3),
+            SizeEntry::new("local_b", 5),
+        ]);
+    }
+}
@@ -0,0 +1,171 @@
+//! Structural validation of a finalized WASM module.
+//!
+//! `wasm-opt` is not expected to ever emit a malformed binary, but when it does, the only
+//! visible symptom used to be a browser failing to load the module much later in the pipeline.
+//! This walks the module the way a minimal module validator (in the spirit of wasmi's
+//! validator) would: checking the header, that every section's declared length stays within the
+//! file, that section ids appear in the order the binary format requires (custom sections
+//! aside), and that the Function and Code sections agree on how many functions there are.
+
+use crate::prelude::*;
+
+use crate::project::wasm::size_profile;
+
+
+/// The order non-custom sections must appear in, per the core WASM binary format. Note that
+/// `DataCount` (id `12`) sits *before* `Code` (id `10`) and `Data` (id `11`) in the actual byte
+/// layout despite having the numerically largest id, so ordering must be checked against this
+/// table rather than by comparing raw section ids.
+const SECTION_ORDER: [u8; 12] = [1, 2, 3, 4, 5, 6, 7, 8, 9, 12, 10, 11];
+
+fn section_order_index(id: u8) -> Option<usize> {
+    SECTION_ORDER.iter().position(|&known_id| known_id == id)
+}
+
+/// Validate the structure of a WASM module, failing with the offending section id and byte
+/// offset on the first inconsistency found.
+pub async fn validate(wasm_path: impl AsRef<Path>) -> Result {
+    let bytes = tokio::fs::read(wasm_path.as_ref()).await?;
+    parse_bytes(&bytes)
+        .with_context(|| format!("Validating WASM module at {}.", wasm_path.as_ref().display()))
+}
+
+/// The synchronous, path-free core of [`validate`], split out so it can be unit tested without
+/// touching the filesystem.
+fn parse_bytes(bytes: &[u8]) -> Result {
+    ensure!(bytes.len() >= 8, "File is too small to be a WASM module.");
+    ensure!(bytes[0..4] == size_profile::WASM_MAGIC, "File does not start with the WASM magic number.");
+    ensure!(bytes[4..8] == size_profile::WASM_VERSION, "File has an unsupported WASM version.");
+
+    let mut pos = 8;
+    let mut last_order_index = None;
+    let mut function_count = 0;
+    let mut code_count = 0;
+    // Id and offset of the last Function or Code section seen, reported on a count mismatch.
+    let mut counted_section = (0u8, 0usize);
+    while pos < bytes.len() {
+        let section_offset = pos;
+        let id = bytes[pos];
+        pos += 1;
+        let payload_len = size_profile::read_uleb128(bytes, &mut pos).with_context(|| {
+            format!("Invalid length prefix for section {id} at offset {section_offset}.")
+        })?;
+        let payload = size_profile::read_bytes(bytes, &mut pos, payload_len as usize)
+            .with_context(|| {
+                format!("Section {id} at offset {section_offset} runs past the end of the file.")
+            })?;
+
+        if id != 0 {
+            let order_index = section_order_index(id).ok_or_else(|| {
+                anyhow!("Unknown section id {id} at offset {section_offset}.")
+            })?;
+            ensure!(
+                last_order_index.map_or(true, |last| order_index > last),
+                "Section {id} at offset {section_offset} is out of order.",
+            );
+            last_order_index = Some(order_index);
+        }
+
+        match id {
+            3 => {
+                function_count = function_section_count(payload).with_context(|| {
+                    format!("Malformed Function section at offset {section_offset}.")
+                })?;
+                counted_section = (id, section_offset);
+            }
+            10 => {
+                let sizes = size_profile::code_section_function_sizes(payload).with_context(
+                    || format!("Malformed Code section at offset {section_offset}."),
+                )?;
+                code_count = sizes.len();
+                counted_section = (id, section_offset);
+            }
+            _ => {}
+        }
+    }
+
+    // A missing Function or Code section is equivalent to an empty one (0 functions), so the
+    // comparison below also catches a non-empty Code section with no Function section at all,
+    // or vice versa.
+    let (counted_id, counted_offset) = counted_section;
+    ensure!(
+        function_count == code_count,
+        "Section {counted_id} at offset {counted_offset}: Function section declares {function_count} functions, but the Code section has {code_count} bodies.",
+    );
+    Ok(())
+}
+
+/// Number of entries in the Function section's vector of type indices.
+fn function_section_count(payload: &[u8]) -> Result<usize> {
+    let mut pos = 0;
+    let count = size_profile::read_uleb128(payload, &mut pos)?;
+    for _ in 0..count {
+        size_profile::read_uleb128(payload, &mut pos)?;
+    }
+    Ok(count as usize)
+}
+
+
+// =============
+// === Tests ===
+// =============
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::project::wasm::size_profile::test_support::*;
+
+    #[test]
+    fn empty_module_is_valid() {
+        let module = header();
+        assert!(parse_bytes(&module).is_ok());
+    }
+
+    #[test]
+    fn data_count_before_code_is_valid() {
+        // Element, DataCount, Code, Data: the actual on-disk order mandated by the spec, which
+        // is *not* ascending by numeric section id (12 appears before 10 and 11).
+        let mut module = header();
+        module.extend(section(9, uleb(0))); // empty Element section
+        module.extend(section(12, uleb(0))); // DataCount: 0 data segments
+        module.extend(section(10, uleb(0))); // empty Code section
+        module.extend(section(11, uleb(0))); // empty Data section
+        assert!(parse_bytes(&module).is_ok(), "a DataCount section before Code must be valid");
+    }
+
+    #[test]
+    fn code_before_data_count_is_invalid() {
+        let mut module = header();
+        module.extend(section(10, uleb(0)));
+        module.extend(section(12, uleb(0)));
+        assert!(parse_bytes(&module).is_err());
+    }
+
+    #[test]
+    fn function_and_code_count_mismatch_is_invalid() {
+        let mut function_payload = uleb(1);
+        function_payload.extend(uleb(0)); // one declared function, type index 0
+
+        let mut module = header();
+        module.extend(section(3, function_payload));
+        module.extend(section(10, uleb(0))); // but zero function bodies
+        assert!(parse_bytes(&module).is_err());
+    }
+
+    #[test]
+    fn truncated_section_length_is_invalid() {
+        let mut module = header();
+        module.push(1); // section id
+        module.push(0x80); // start of a LEB128 continuation byte with nothing following
+        assert!(parse_bytes(&module).is_err());
+    }
+
+    #[test]
+    fn section_overrunning_the_file_is_invalid() {
+        let mut module = header();
+        module.push(1); // section id
+        module.extend(uleb(10)); // claims 10 bytes of payload
+        module.extend(vec![0u8; 2]); // but only 2 are present
+        assert!(parse_bytes(&module).is_err());
+    }
+}